@@ -6,7 +6,7 @@ use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::surface::Surface;
 use std::io::{Cursor, Read};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct Pixel(u8, u8, u8, u8);
 
 impl Pixel {
@@ -19,21 +19,45 @@ impl Pixel {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Channels {
     Rgb,
     Rgba,
 }
 
+impl Channels {
+    fn byte_count(self) -> u32 {
+        match self {
+            Channels::Rgb => 3,
+            Channels::Rgba => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Colorspace {
     Srgb,
     Linear,
 }
 
+/// Converts an 8-bit linear-light sample to sRGB gamma (computed on the value
+/// normalized to `[0,1]`), per the QOI spec's colorspace byte. Alpha is never linear-encoded and must not be
+/// passed through this.
+fn linear_to_srgb(c: u8) -> u8 {
+    let normalized = c as f32 / 255.0;
+    let converted = if normalized > 0.0031308 {
+        1.055 * normalized.powf(1.0 / 2.4) - 0.055
+    } else {
+        12.92 * normalized
+    };
+    (converted.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
 struct QoiImage {
     width: u32,
     height: u32,
     channels: Channels,
-    _colorspace: Colorspace,
+    colorspace: Colorspace,
     pixels: Vec<u8>,
 }
 
@@ -159,7 +183,7 @@ impl QoiImage {
             } else {
                 Channels::Rgba
             },
-            _colorspace: if colorspace == 0 {
+            colorspace: if colorspace == 0 {
                 Colorspace::Srgb
             } else {
                 Colorspace::Linear
@@ -168,26 +192,328 @@ impl QoiImage {
         })
     }
 
-    fn pitch(&self) -> u32 {
-        self.width * self.bytes_per_pixel()
+    fn encode(&self) -> Vec<u8> {
+        let channels = self.bytes_per_pixel() as u8;
+        let num_pixel_bytes = self.width as usize * self.height as usize * channels as usize;
+
+        let mut out = Vec::with_capacity(num_pixel_bytes);
+        out.extend_from_slice(&Self::MAGIC);
+        out.extend_from_slice(&self.width.to_be_bytes());
+        out.extend_from_slice(&self.height.to_be_bytes());
+        out.push(channels);
+        out.push(match self.colorspace {
+            Colorspace::Srgb => 0,
+            Colorspace::Linear => 1,
+        });
+
+        let mut prev_pixel = Pixel(0, 0, 0, 255);
+        let mut seen_pixels = [Pixel(0, 0, 0, 0); 64];
+        seen_pixels[prev_pixel.hash()] = prev_pixel;
+
+        let mut run = 0u8;
+
+        for chunk in self.pixels.chunks_exact(channels as usize) {
+            let a = if channels == 4 { chunk[3] } else { prev_pixel.3 };
+            let p = Pixel(chunk[0], chunk[1], chunk[2], a);
+
+            if p == prev_pixel {
+                run += 1;
+                if run == 62 {
+                    out.push(0b1100_0000 | (run - 1));
+                    run = 0;
+                }
+                continue;
+            }
+
+            if run > 0 {
+                out.push(0b1100_0000 | (run - 1));
+                run = 0;
+            }
+
+            let hash = p.hash();
+            if seen_pixels[hash] == p {
+                out.push(hash as u8);
+            } else if p.3 == prev_pixel.3 {
+                let dr = p.0.wrapping_sub(prev_pixel.0) as i8;
+                let dg = p.1.wrapping_sub(prev_pixel.1) as i8;
+                let db = p.2.wrapping_sub(prev_pixel.2) as i8;
+
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(
+                        0b0100_0000
+                            | (((dr + 2) as u8) << 4)
+                            | (((dg + 2) as u8) << 2)
+                            | (db + 2) as u8,
+                    );
+                } else {
+                    let dr_dg = dr.wrapping_sub(dg);
+                    let db_dg = db.wrapping_sub(dg);
+                    if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg)
+                    {
+                        out.push(0b1000_0000 | (dg + 32) as u8);
+                        out.push((((dr_dg + 8) as u8) << 4) | (db_dg + 8) as u8);
+                    } else {
+                        out.push(0xFE);
+                        out.push(p.0);
+                        out.push(p.1);
+                        out.push(p.2);
+                    }
+                }
+            } else {
+                out.push(0xFF);
+                out.push(p.0);
+                out.push(p.1);
+                out.push(p.2);
+                out.push(p.3);
+            }
+
+            prev_pixel = p;
+            seen_pixels[hash] = p;
+        }
+
+        if run > 0 {
+            out.push(0b1100_0000 | (run - 1));
+        }
+
+        out.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+        out
     }
 
     fn bytes_per_pixel(&self) -> u32 {
-        match self.channels {
-            Channels::Rgb => 3,
-            Channels::Rgba => 4,
+        self.channels.byte_count()
+    }
+}
+
+/// Incremental QOI decoder that parses one pixel at a time from `R`, so a
+/// caller never has to hold the fully-decoded image in memory alongside the
+/// still-compressed source.
+struct QoiReader<R: Read> {
+    reader: R,
+    width: u32,
+    height: u32,
+    output_channels: Channels,
+    colorspace: Colorspace,
+    prev_pixel: Pixel,
+    seen_pixels: [Pixel; 64],
+    remaining_run_length: u8,
+    pixels_read: usize,
+    end_marker_checked: bool,
+}
+
+impl<R: Read> QoiReader<R> {
+    fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        if magic != QoiImage::MAGIC {
+            bail!("invalid magic bytes");
+        }
+
+        let width = reader.read_u32::<BigEndian>().context("read width")?;
+        let height = reader.read_u32::<BigEndian>().context("read height")?;
+        let channels = reader.read_u8().context("read num channels")?;
+        let colorspace = reader.read_u8().context("read colorspace")?;
+
+        if channels != 3 && channels != 4 {
+            bail!("invalid number of channels");
+        }
+
+        if colorspace != 0 && colorspace != 1 {
+            bail!("invalid colorspace");
         }
+
+        let prev_pixel = Pixel(0, 0, 0, 255);
+        let mut seen_pixels = [Pixel(0, 0, 0, 0); 64];
+        seen_pixels[prev_pixel.hash()] = prev_pixel;
+
+        let channels = if channels == 3 {
+            Channels::Rgb
+        } else {
+            Channels::Rgba
+        };
+
+        Ok(Self {
+            reader,
+            width,
+            height,
+            output_channels: channels,
+            colorspace: if colorspace == 0 {
+                Colorspace::Srgb
+            } else {
+                Colorspace::Linear
+            },
+            prev_pixel,
+            seen_pixels,
+            remaining_run_length: 0,
+            pixels_read: 0,
+            end_marker_checked: false,
+        })
+    }
+
+    /// Forces `next_pixel` output to `channels` regardless of how many
+    /// channels the file itself stores, e.g. requesting `Rgba` out of a
+    /// 3-channel file pads alpha to `255`, and requesting `Rgb` out of a
+    /// 4-channel file drops it. `Pixel` always carries all four components
+    /// internally, so this only changes how many bytes `channels()` reports
+    /// should be written per pixel.
+    fn with_channels(mut self, channels: Channels) -> Self {
+        self.output_channels = channels;
+        self
+    }
+
+    fn channels(&self) -> Channels {
+        self.output_channels
     }
+
+    fn colorspace(&self) -> Colorspace {
+        self.colorspace
+    }
+
+    fn total_pixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    fn next_pixel(&mut self) -> anyhow::Result<Option<Pixel>> {
+        if self.pixels_read >= self.total_pixels() {
+            if !self.end_marker_checked {
+                self.end_marker_checked = true;
+
+                let mut end_marker = [0u8; 8];
+                self.reader
+                    .read_exact(&mut end_marker)
+                    .context("read byte stream end marker")?;
+                if end_marker != [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01] {
+                    bail!("invalid byte stream end marker");
+                }
+            }
+
+            return Ok(None);
+        }
+
+        if self.remaining_run_length > 0 {
+            self.remaining_run_length -= 1;
+            self.pixels_read += 1;
+            return Ok(Some(self.prev_pixel));
+        }
+
+        let op = self.reader.read_u8().context("read op")?;
+        let p = if op == 0b1111_1110 {
+            // QOI_OP_RGB
+            let r = self.reader.read_u8().context("QOI_OP_RGB read r")?;
+            let g = self.reader.read_u8().context("QOI_OP_RGB read g")?;
+            let b = self.reader.read_u8().context("QOI_OP_RGB read b")?;
+            let a = self.prev_pixel.3;
+            Pixel(r, g, b, a)
+        } else if op == 0b1111_1111 {
+            // QOI_OP_RGBA
+            let r = self.reader.read_u8().context("QOI_OP_RGBA read r")?;
+            let g = self.reader.read_u8().context("QOI_OP_RGBA read g")?;
+            let b = self.reader.read_u8().context("QOI_OP_RGBA read b")?;
+            let a = self.reader.read_u8().context("QOI_OP_RGBA read a")?;
+            Pixel(r, g, b, a)
+        } else if op & 0b1100_0000 == 0b0000_0000 {
+            // QOI_OP_INDEX
+            let idx = op & 0b0011_1111;
+            self.seen_pixels[idx as usize]
+        } else if op & 0b1100_0000 == 0b0100_0000 {
+            // QOI_OP_DIFF
+            let dr = (op >> 4) & 0b11;
+            let dg = (op >> 2) & 0b11;
+            let db = op & 0b11;
+            Pixel(
+                self.prev_pixel.0.wrapping_add(dr).wrapping_sub(2),
+                self.prev_pixel.1.wrapping_add(dg).wrapping_sub(2),
+                self.prev_pixel.2.wrapping_add(db).wrapping_sub(2),
+                self.prev_pixel.3,
+            )
+        } else if op & 0b1100_0000 == 0b1000_0000 {
+            // QOI_OP_LUMA
+            let next_byte = self.reader.read_u8().context("QOI_OP_LUMA read next byte")?;
+
+            let dg = (op & 0b0011_1111).wrapping_sub(32);
+            let dr = (next_byte >> 4).wrapping_add(dg).wrapping_sub(8);
+            let db = (next_byte & 0xF).wrapping_add(dg).wrapping_sub(8);
+
+            Pixel(
+                self.prev_pixel.0.wrapping_add(dr),
+                self.prev_pixel.1.wrapping_add(dg),
+                self.prev_pixel.2.wrapping_add(db),
+                self.prev_pixel.3,
+            )
+        } else if op & 0b1100_0000 == 0b1100_0000 {
+            // QOI_OP_RUN
+            let run = (op & 0b0011_1111) + 1;
+            self.remaining_run_length = run - 1;
+            self.prev_pixel
+        } else {
+            bail!("invalid op")
+        };
+
+        self.prev_pixel = p;
+        self.seen_pixels[p.hash()] = p;
+        self.pixels_read += 1;
+
+        Ok(Some(p))
+    }
+}
+
+impl<R: Read> Iterator for QoiReader<R> {
+    type Item = anyhow::Result<Pixel>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pixel().transpose()
+    }
+}
+
+fn run_encode(in_path: &str, out_path: &str) -> anyhow::Result<()> {
+    let data = std::fs::read(in_path).context("reading file")?;
+    let image = QoiImage::decode(&data).context("parsing qoi file")?;
+    std::fs::write(out_path, image.encode()).context("writing qoi file")?;
+    Ok(())
 }
 
 pub fn main() -> anyhow::Result<()> {
-    let Some(filepath) = std::env::args().nth(1) else {
-        bail!("usage: qoi_viewer <path>");
+    let mut args = std::env::args().skip(1);
+    let Some(first) = args.next() else {
+        bail!("usage: qoi_viewer <path> [--raw] | qoi_viewer encode <in> <out>");
     };
 
-    let data = std::fs::read(&filepath).context("reading file")?;
+    if first == "encode" {
+        let in_path = args.next().context("missing input path")?;
+        let out_path = args.next().context("missing output path")?;
+        return run_encode(&in_path, &out_path);
+    }
 
-    let image = QoiImage::decode(&data).context("parsing qoi file")?;
+    let filepath = first;
+    let raw = args.next().as_deref() == Some("--raw");
+
+    let file = std::fs::File::open(&filepath).context("opening file")?;
+    let mut reader = QoiReader::new(std::io::BufReader::new(file))
+        .context("parsing qoi header")?
+        .with_channels(Channels::Rgba);
+
+    let width = reader.width;
+    let height = reader.height;
+    let bytes_per_pixel = reader.channels().byte_count();
+    let convert_to_srgb = !raw && reader.colorspace() == Colorspace::Linear;
+
+    let mut pixel_data =
+        Vec::with_capacity(width as usize * height as usize * bytes_per_pixel as usize);
+    while let Some(p) = reader.next_pixel().context("decoding pixel")? {
+        if convert_to_srgb {
+            pixel_data.push(linear_to_srgb(p.0));
+            pixel_data.push(linear_to_srgb(p.1));
+            pixel_data.push(linear_to_srgb(p.2));
+        } else {
+            pixel_data.push(p.0);
+            pixel_data.push(p.1);
+            pixel_data.push(p.2);
+        }
+        if bytes_per_pixel == 4 {
+            pixel_data.push(p.3);
+        }
+    }
 
     let sdl_context = sdl2::init()
         .map_err(|e| anyhow!(e))
@@ -199,7 +525,7 @@ pub fn main() -> anyhow::Result<()> {
         .context("initializing video subsystem")?;
 
     let window = video_subsystem
-        .window(&filepath, image.width, image.height)
+        .window(&filepath, width, height)
         .position_centered()
         .build()
         .map_err(|e| anyhow!(e))
@@ -211,16 +537,12 @@ pub fn main() -> anyhow::Result<()> {
         .map_err(|e| anyhow!(e))
         .context("creating canvas")?;
 
-    let mut pixel_data = image.pixels.clone();
     let surface = Surface::from_data(
         &mut pixel_data,
-        image.width,
-        image.height,
-        image.pitch(),
-        match image.channels {
-            Channels::Rgb => PixelFormatEnum::RGB24,
-            Channels::Rgba => PixelFormatEnum::RGBA32,
-        },
+        width,
+        height,
+        width * bytes_per_pixel,
+        PixelFormatEnum::RGBA32,
     )
     .map_err(|e| anyhow!(e))
     .context("creating surface from image")?;
@@ -255,3 +577,193 @@ pub fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift64 PRNG, seeded per test run so failures are
+    /// reproducible without pulling in an external RNG crate.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            Self(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn next_u8(&mut self) -> u8 {
+            self.next_u64() as u8
+        }
+
+        fn next_f64(&mut self) -> f64 {
+            (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn next_range(&mut self, low: i32, high_inclusive: i32) -> i32 {
+            let span = (high_inclusive - low + 1) as u64;
+            low + (self.next_u64() % span) as i32
+        }
+    }
+
+    /// Generates pixel sequences that are biased towards triggering every QOI
+    /// opcode (`RGB`/`RGBA` fresh pixels, `INDEX`, `RUN`, `DIFF`, `LUMA`), so
+    /// that encoding and decoding the result exercises the full codec rather
+    /// than only the fresh-pixel fallback path.
+    struct ImageGen {
+        rng: Rng,
+        // Cumulative thresholds over [0, 1) for, in order: new RGB pixel, new
+        // RGBA pixel, index hit, repeat-previous, small diff, luma diff.
+        thresholds: [f64; 6],
+    }
+
+    impl ImageGen {
+        fn new(seed: u64) -> Self {
+            let mut rng = Rng::new(seed);
+            let mut raw = [0.0; 6];
+            for slot in raw.iter_mut() {
+                *slot = rng.next_f64().max(0.001);
+            }
+            let sum: f64 = raw.iter().sum();
+
+            let mut thresholds = [0.0; 6];
+            let mut acc = 0.0;
+            for (threshold, p) in thresholds.iter_mut().zip(raw.iter()) {
+                acc += p / sum;
+                *threshold = acc;
+            }
+
+            Self { rng, thresholds }
+        }
+
+        fn generate(&mut self, count: usize, channels: Channels) -> Vec<u8> {
+            let has_alpha = channels == Channels::Rgba;
+
+            let mut prev = Pixel(0, 0, 0, 255);
+            let mut seen_pixels = [Pixel(0, 0, 0, 0); 64];
+            seen_pixels[prev.hash()] = prev;
+
+            let mut bytes = Vec::with_capacity(count * channels.byte_count() as usize);
+
+            for _ in 0..count {
+                let roll = self.rng.next_f64();
+                let p = if roll < self.thresholds[0] {
+                    // new RGB pixel: alpha carried over, like a real 3-channel stream would.
+                    Pixel(self.rng.next_u8(), self.rng.next_u8(), self.rng.next_u8(), prev.3)
+                } else if roll < self.thresholds[1] {
+                    if has_alpha {
+                        Pixel(
+                            self.rng.next_u8(),
+                            self.rng.next_u8(),
+                            self.rng.next_u8(),
+                            self.rng.next_u8(),
+                        )
+                    } else {
+                        Pixel(self.rng.next_u8(), self.rng.next_u8(), self.rng.next_u8(), prev.3)
+                    }
+                } else if roll < self.thresholds[2] {
+                    let idx = self.rng.next_range(0, 63) as usize;
+                    seen_pixels[idx]
+                } else if roll < self.thresholds[3] {
+                    prev
+                } else if roll < self.thresholds[4] {
+                    let dr = self.rng.next_range(-2, 1);
+                    let dg = self.rng.next_range(-2, 1);
+                    let db = self.rng.next_range(-2, 1);
+                    Pixel(
+                        prev.0.wrapping_add(dr as u8),
+                        prev.1.wrapping_add(dg as u8),
+                        prev.2.wrapping_add(db as u8),
+                        prev.3,
+                    )
+                } else {
+                    let dg = self.rng.next_range(-32, 31);
+                    let dr_dg = self.rng.next_range(-8, 7);
+                    let db_dg = self.rng.next_range(-8, 7);
+                    Pixel(
+                        prev.0.wrapping_add((dg + dr_dg) as u8),
+                        prev.1.wrapping_add(dg as u8),
+                        prev.2.wrapping_add((dg + db_dg) as u8),
+                        prev.3,
+                    )
+                };
+
+                bytes.push(p.0);
+                bytes.push(p.1);
+                bytes.push(p.2);
+                if has_alpha {
+                    bytes.push(p.3);
+                }
+
+                seen_pixels[p.hash()] = p;
+                prev = p;
+            }
+
+            bytes
+        }
+    }
+
+    fn round_trip(channels: Channels, seed: u64) {
+        let width = 17;
+        let height = 13;
+        let pixels = ImageGen::new(seed).generate(width as usize * height as usize, channels);
+
+        let image = QoiImage {
+            width,
+            height,
+            channels,
+            colorspace: Colorspace::Srgb,
+            pixels,
+        };
+
+        let encoded = image.encode();
+        let decoded = QoiImage::decode(&encoded).expect("round-tripped qoi data should decode");
+
+        assert_eq!(
+            decoded.pixels, image.pixels,
+            "seed {seed} channels {channels:?} did not round-trip"
+        );
+
+        let mut reader = QoiReader::new(Cursor::new(&encoded))
+            .expect("round-tripped qoi data should parse a header")
+            .with_channels(channels);
+
+        let mut streamed = Vec::with_capacity(image.pixels.len());
+        while let Some(p) = reader
+            .next_pixel()
+            .expect("round-tripped qoi data should stream-decode")
+        {
+            streamed.push(p.0);
+            streamed.push(p.1);
+            streamed.push(p.2);
+            if channels == Channels::Rgba {
+                streamed.push(p.3);
+            }
+        }
+
+        assert_eq!(
+            streamed, image.pixels,
+            "seed {seed} channels {channels:?} did not round-trip via QoiReader"
+        );
+    }
+
+    #[test]
+    fn encode_decode_round_trip_rgb() {
+        for seed in 0..64 {
+            round_trip(Channels::Rgb, seed);
+        }
+    }
+
+    #[test]
+    fn encode_decode_round_trip_rgba() {
+        for seed in 0..64 {
+            round_trip(Channels::Rgba, seed);
+        }
+    }
+}